@@ -12,6 +12,7 @@
 //! the host VMM.
 
 use super::*;
+use alloc::vec::Vec;
 use core::arch::asm;
 use core::result::Result;
 use core::sync::atomic::{fence, Ordering};
@@ -416,6 +417,224 @@ pub fn tdvmcall_get_quote(buffer: &mut [u8]) -> Result<(), TdVmcallError> {
     Ok(())
 }
 
+/// Header of the GHCI `TDG.VP.VMCALL<GetQuote>` shared buffer, version 1.
+///
+/// Callers of `tdvmcall_get_quote` must format the shared buffer with this header followed by
+/// the TDREPORT bytes; on completion the same region holds this header followed by the quote.
+/// `get_quote_blocking` builds and parses it automatically.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct GetQuoteHeader {
+    /// Header version, currently 1.
+    version: u64,
+    /// Status of the GetQuote operation. Written by the host; `0` once the quote is ready.
+    status: u32,
+    /// Total length of the buffer, including this header.
+    in_len: u32,
+    /// Length of the data following this header: the TDREPORT on input, the quote on output.
+    out_len: u32,
+}
+
+const GET_QUOTE_HEADER_VERSION: u64 = 1;
+const GET_QUOTE_STATUS_SUCCESS: u32 = 0;
+/// Sentinel written into `status` before the GetQuote TDVMCALL is issued, so the wait loop can
+/// tell "the host hasn't processed this yet" apart from a genuine `GET_QUOTE_STATUS_SUCCESS`.
+const GET_QUOTE_STATUS_IN_FLIGHT: u32 = 0xffff_ffff;
+const GET_QUOTE_BUFFER_SIZE: usize = PAGE_SIZE_4K as usize;
+
+/// Interrupt vector used to signal GetQuote completion, registered with
+/// `tdvmcall_setup_event_notify` by `get_quote_blocking`.
+const GET_QUOTE_NOTIFY_VECTOR: u64 = 0x51;
+
+/// Backing storage for the GetQuote shared buffer.
+///
+/// `tdvmcall_get_quote` requires "a piece of 4KB-aligned shared memory": the alignment is
+/// enforced here, and `get_quote_blocking` converts the page to shared with `tdvmcall_mapgpa`
+/// before handing it to the host.
+#[repr(C, align(4096))]
+struct GetQuoteBuffer([u8; GET_QUOTE_BUFFER_SIZE]);
+
+/// Dedicated scratch page for `get_quote_blocking`, rather than a stack local.
+///
+/// Its private/shared conversion state (and, on the way back, its TDG.MEM.PAGE.ACCEPT
+/// acceptance state) outlives the function call across the host round-trip, so it must live at
+/// a fixed physical address instead of wherever the caller's stack happens to be; otherwise a
+/// leaked conversion on an error path would corrupt whatever unrelated code next reuses that
+/// stack slot.
+static mut GET_QUOTE_BUFFER: GetQuoteBuffer = GetQuoteBuffer([0u8; GET_QUOTE_BUFFER_SIZE]);
+
+/// Request a TD-Quote covering `report` and block until the host Quoting Enclave has produced
+/// it.
+///
+/// Registers a completion vector with `tdvmcall_setup_event_notify`, converts the dedicated
+/// `GET_QUOTE_BUFFER` page to shared memory, formats it with the GHCI GetQuote header carrying
+/// the TDREPORT, issues the GetQuote TDVMCALL, and `tdvmcall_sti_halt`s until the completion
+/// interrupt fires and the buffer's status field reports the quote is ready, then returns the
+/// quote bytes. The buffer is converted back to private and re-accepted on every return path
+/// once it has been shared, success or failure.
+///
+/// Details can be found in TDX GHCI spec section 'TDG.VP.VMCALL<GetQuote>'.
+pub fn get_quote_blocking(report: &TdReport) -> Result<Vec<u8>, TdVmcallError> {
+    tdvmcall_setup_event_notify(GET_QUOTE_NOTIFY_VECTOR)?;
+    // Best-effort: without this the TDX module may never actually deliver the vector
+    // registered above, but GetQuote completion can still be observed by polling on wakeup.
+    let _ = enable_virtual_interrupt_notifications();
+
+    // Safety: `get_quote_blocking` is the sole accessor of `GET_QUOTE_BUFFER` and TD guests are
+    // single-threaded at this point in boot.
+    let buffer = unsafe { &mut GET_QUOTE_BUFFER };
+    let paddr = core::ptr::addr_of!(*buffer) as u64;
+    tdvmcall_mapgpa(true, paddr, GET_QUOTE_BUFFER_SIZE)?;
+
+    let header_size = core::mem::size_of::<GetQuoteHeader>();
+    let report_bytes = unsafe {
+        core::slice::from_raw_parts(
+            core::ptr::addr_of!(*report) as *const u8,
+            core::mem::size_of::<TdReport>(),
+        )
+    };
+
+    let header = GetQuoteHeader {
+        version: GET_QUOTE_HEADER_VERSION,
+        status: GET_QUOTE_STATUS_IN_FLIGHT,
+        in_len: (header_size + report_bytes.len()) as u32,
+        out_len: report_bytes.len() as u32,
+    };
+    let header_bytes = unsafe {
+        core::slice::from_raw_parts(core::ptr::addr_of!(header) as *const u8, header_size)
+    };
+
+    buffer.0[..header_size].copy_from_slice(header_bytes);
+    buffer.0[header_size..header_size + report_bytes.len()].copy_from_slice(report_bytes);
+
+    if let Err(e) = tdvmcall_get_quote(&mut buffer.0) {
+        reclaim_get_quote_buffer(paddr);
+        return Err(e);
+    }
+
+    let quote = loop {
+        tdvmcall_sti_halt();
+
+        let header = unsafe { &*(buffer.0.as_ptr() as *const GetQuoteHeader) };
+        match header.status {
+            GET_QUOTE_STATUS_IN_FLIGHT => continue,
+            GET_QUOTE_STATUS_SUCCESS if header.out_len > 0 => {
+                let start = header_size;
+                let end = start + header.out_len as usize;
+                break buffer.0[start..end].to_vec();
+            }
+            // Any other status is a genuine GHCI error/failure code from the host or the
+            // Quoting Enclave, and a success status with an empty quote is equally unusable:
+            // treat both as terminal instead of looping forever.
+            status => {
+                reclaim_get_quote_buffer(paddr);
+                return Err((status as u64).into());
+            }
+        }
+    };
+
+    reclaim_get_quote_buffer(paddr);
+
+    Ok(quote)
+}
+
+/// Convert the GetQuote scratch page back to private and re-accept it.
+///
+/// A shared-to-private `tdvmcall_mapgpa` conversion leaves the page pending until
+/// `TDG.MEM.PAGE.ACCEPT` runs again (see `tdcall_accept_page`'s doc comment); skipping that
+/// would make the next ordinary access to this page fault as an EPT violation and get
+/// misinterpreted as MMIO.
+fn reclaim_get_quote_buffer(paddr: u64) {
+    if tdvmcall_mapgpa(false, paddr, GET_QUOTE_BUFFER_SIZE).is_ok() {
+        let _ = tdcall_accept_page(paddr);
+    }
+}
+
+/// Size of the shared ASCII message buffer accompanying `TDG.VP.VMCALL<ReportFatalError>`.
+const REPORT_FATAL_ERROR_MSG_SIZE: usize = 64;
+
+/// Bit 63 of the R12 error-code register marks the error as fatal (non-recoverable).
+///
+/// Details can be found in TDX GHCI spec section 'TDG.VP.VMCALL<ReportFatalError>'.
+const REPORT_FATAL_ERROR_FATAL_BIT: u64 = 1 << 63;
+
+/// Report a fatal guest error to the host VMM.
+///
+/// `error_code` is passed to the VMM with its top bit set to mark the error non-recoverable.
+/// `message` is copied both into the GPR-embedded short message (R14/R15, 16 bytes) and into a
+/// 64-byte ASCII buffer whose GPA is passed in R13, so the VMM can log or display it; the
+/// message is truncated if longer than the buffer. The call does not return: the VMM is expected
+/// to terminate the TD, but this function halts in a loop in case it does not.
+///
+/// Details can be found in TDX GHCI spec section 'TDG.VP.VMCALL<ReportFatalError>'.
+pub fn tdvmcall_report_fatal_error(error_code: u64, message: &[u8]) -> ! {
+    let mut inline_msg = [0u8; 16];
+    let inline_len = core::cmp::min(message.len(), inline_msg.len());
+    inline_msg[..inline_len].copy_from_slice(&message[..inline_len]);
+    let r14 = u64::from_le_bytes(inline_msg[0..8].try_into().unwrap());
+    let r15 = u64::from_le_bytes(inline_msg[8..16].try_into().unwrap());
+
+    let mut shared_msg = [0u8; REPORT_FATAL_ERROR_MSG_SIZE];
+    let shared_len = core::cmp::min(message.len(), shared_msg.len());
+    shared_msg[..shared_len].copy_from_slice(&message[..shared_len]);
+
+    // Setting the shared address bit alone doesn't make the host able to read this page; the
+    // SEPT entry has to actually be converted. The call never returns, so there is no matching
+    // conversion back to private.
+    let shared_msg_addr = core::ptr::addr_of!(shared_msg) as u64;
+    let _ = tdvmcall_mapgpa(true, shared_msg_addr, REPORT_FATAL_ERROR_MSG_SIZE);
+
+    let mut args = TdVmcallArgs {
+        r11: TDVMCALL_REPORT_FATAL_ERROR,
+        r12: error_code | REPORT_FATAL_ERROR_FATAL_BIT,
+        r13: shared_msg_addr | *SHARED_MASK,
+        r14,
+        r15,
+        ..Default::default()
+    };
+
+    let _ = td_vmcall(&mut args);
+
+    loop {
+        tdvmcall_halt();
+    }
+}
+
+/// Buffer used to format a Rust panic message for `tdvmcall_report_fatal_error`.
+#[derive(Default)]
+struct PanicMessageBuffer {
+    data: [u8; REPORT_FATAL_ERROR_MSG_SIZE],
+    len: usize,
+}
+
+impl core::fmt::Write for PanicMessageBuffer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let remaining = self.data.len() - self.len;
+        let n = core::cmp::min(remaining, s.len());
+        self.data[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Report a Rust panic to the host VMM as a fatal error before halting.
+///
+/// Intended to be called from the crate's `#[panic_handler]`, e.g.:
+/// ```ignore
+/// #[panic_handler]
+/// fn panic(info: &core::panic::PanicInfo) -> ! {
+///     tdx_tdcall::report_panic(info)
+/// }
+/// ```
+pub fn report_panic(info: &core::panic::PanicInfo) -> ! {
+    use core::fmt::Write;
+
+    let mut buf = PanicMessageBuffer::default();
+    let _ = write!(buf, "{}", info);
+
+    tdvmcall_report_fatal_error(1, &buf.data[..buf.len])
+}
+
 /// Get guest TD execution environment information
 ///
 /// Details can be found in TDX Module ABI spec section 'TDG.VP.INFO Leaf'
@@ -558,6 +777,167 @@ pub fn td_accept_memory(address: u64, len: u64) {
     }
 }
 
+/// `REPORT_TYPE` sub-field of the REPORTMACSTRUCT, identifying what kind of report was produced.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ReportType {
+    pub r_type: u8,
+    pub subtype: u8,
+    pub version: u8,
+    pub reserved: u8,
+}
+
+/// REPORTMACSTRUCT: the MAC-protected header of a TDREPORT_STRUCT.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ReportMacStruct {
+    pub report_type: ReportType,
+    pub reserved0: [u8; 12],
+    pub cpu_svn: [u8; 16],
+    pub tee_tcb_info_hash: [u8; 48],
+    pub tee_info_hash: [u8; 48],
+    pub report_data: [u8; 64],
+    pub reserved1: [u8; 32],
+    pub mac: [u8; 32],
+}
+
+/// TEE_TCB_INFO: additional TCB details of the TDX module that produced the report.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TeeTcbInfo {
+    pub valid: [u8; 8],
+    pub tee_tcb_svn: [u8; 16],
+    pub mrseam: [u8; 48],
+    pub mrsignerseam: [u8; 48],
+    pub attributes: [u8; 8],
+    pub tee_tcb_svn2: [u8; 16],
+    pub reserved: [u8; 95],
+}
+
+/// TDINFO_STRUCT: the measurements and configuration of the TD itself, including the RTMR
+/// values extended by `tdcall_extend_rtmr`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TdInfoStruct {
+    pub attributes: u64,
+    pub xfam: u64,
+    pub mrtd: [u8; 48],
+    pub mrconfigid: [u8; 48],
+    pub mrowner: [u8; 48],
+    pub mrownerconfig: [u8; 48],
+    pub rtmr: [[u8; 48]; 4],
+    pub servtd_hash: [u8; 48],
+    pub reserved: [u8; 64],
+}
+
+/// TDREPORT_STRUCT produced by TDG.MR.REPORT: a local attestation report covering the
+/// REPORTMACSTRUCT, TEE_TCB_INFO and TDINFO_STRUCT. Passing it to `get_quote_blocking` (which
+/// wraps its bytes in the GetQuote buffer header) obtains a remotely verifiable quote.
+///
+/// The 1024-byte size and 1024-byte alignment are mandated by the TDX Module ABI spec so the
+/// struct can be used directly as the TDG.MR.REPORT output buffer.
+#[repr(C, align(1024))]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TdReport {
+    pub report_mac_struct: ReportMacStruct,
+    pub tee_tcb_info: TeeTcbInfo,
+    pub reserved: [u8; 17],
+    pub td_info: TdInfoStruct,
+}
+
+/// Generate a TDREPORT_STRUCT for local attestation.
+///
+/// `report_data` is a caller-supplied 64-byte nonce (e.g. a random value, or a hash binding the
+/// report to a higher-level protocol) that is embedded in the REPORTMACSTRUCT and covered by its
+/// MAC, so the resulting report can be passed to `get_quote_blocking` to produce a quote over
+/// caller-chosen data.
+///
+/// Details can be found in TDX Module ABI spec section 'TDG.MR.REPORT Leaf'.
+pub fn tdcall_get_report(report_data: &[u8; 64]) -> Result<TdReport, TdCallError> {
+    let mut report = TdReport::default();
+
+    let mut args = TdcallArgs {
+        rax: TDCALL_TDREPORT,
+        rcx: core::ptr::addr_of_mut!(report) as u64,
+        rdx: report_data.as_ptr() as u64,
+        r8: 0,
+        ..Default::default()
+    };
+
+    let ret = td_call(&mut args);
+
+    if ret != TDCALL_STATUS_SUCCESS {
+        return Err(ret.into());
+    }
+
+    Ok(report)
+}
+
+/// Read a single TDCS/TDVPS metadata field via TDG.VM.RD.
+///
+/// `field_id` identifies the field per the TDX Module ABI spec's metadata field ID encoding,
+/// e.g. the TDCS_NOTIFY_ENABLES field used by `enable_virtual_interrupt_notifications`.
+///
+/// Details can be found in TDX Module ABI spec section 'TDG.VM.RD Leaf'.
+pub fn tdcall_vm_read(field_id: u64) -> Result<u64, TdCallError> {
+    let mut args = TdcallArgs {
+        rax: TDCALL_TDVMRD,
+        rdx: field_id,
+        ..Default::default()
+    };
+
+    let ret = td_call(&mut args);
+
+    if ret != TDCALL_STATUS_SUCCESS {
+        return Err(ret.into());
+    }
+
+    Ok(args.r8)
+}
+
+/// Write a single TDCS/TDVPS metadata field via TDG.VM.WR, optionally restricting the write to
+/// the bits set in `write_mask`, and return the field's resulting value.
+///
+/// Details can be found in TDX Module ABI spec section 'TDG.VM.WR Leaf'.
+pub fn tdcall_vm_write(field_id: u64, value: u64, write_mask: u64) -> Result<u64, TdCallError> {
+    let mut args = TdcallArgs {
+        rax: TDCALL_TDVMWR,
+        rdx: field_id,
+        r8: value,
+        r9: write_mask,
+        ..Default::default()
+    };
+
+    let ret = td_call(&mut args);
+
+    if ret != TDCALL_STATUS_SUCCESS {
+        return Err(ret.into());
+    }
+
+    Ok(args.r8)
+}
+
+/// TDCS_NOTIFY_ENABLES metadata field ID.
+///
+/// Details can be found in TDX Module ABI spec section 'TDVPS(excluding TD VMCS)'.
+const TDCS_NOTIFY_ENABLES: u64 = 0x9100_0000_0000_0010;
+
+/// Bit 0 of TDCS_NOTIFY_ENABLES: enables delivery of the event-notify interrupt vector
+/// registered with `tdvmcall_setup_event_notify`.
+const NOTIFY_ENABLES_VECTOR_EN: u64 = 1;
+
+/// Enable virtual-interrupt event-notify delivery, so the guest actually receives the
+/// interrupts configured by `tdvmcall_setup_event_notify`.
+pub fn enable_virtual_interrupt_notifications() -> Result<(), TdCallError> {
+    tdcall_vm_write(
+        TDCS_NOTIFY_ENABLES,
+        NOTIFY_ENABLES_VECTOR_EN,
+        NOTIFY_ENABLES_VECTOR_EN,
+    )?;
+
+    Ok(())
+}
+
 /// Get the guest physical address (GPA) width via TDG.VP.INFO
 /// The GPA width can be used to determine the shared-bit of GPA
 pub fn td_shared_mask() -> Option<u64> {
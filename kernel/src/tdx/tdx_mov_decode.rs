@@ -0,0 +1,179 @@
+// Copyright (c) 2020-2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! Minimal x86-64 MOV instruction decoder used to emulate EPT-violation #VEs caused by MMIO
+//! access.
+//!
+//! Only the MOV forms a compiler emits for a volatile MMIO load or store are handled: an
+//! 8/16/32/64-bit general-purpose register moved to or from memory, or an immediate moved to
+//! memory (the form `write_volatile` with a constant commonly compiles down to), addressed
+//! through the common ModRM/SIB/REX forms. This is not a general-purpose disassembler.
+
+/// Direction of a decoded MMIO access.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MmioDirection {
+    Read,
+    Write,
+}
+
+/// A decoded MOV instruction that accesses memory.
+#[derive(Copy, Clone, Debug)]
+pub struct DecodedMov {
+    pub direction: MmioDirection,
+    /// Size of the memory operand, in bytes: 1, 2, 4 or 8.
+    pub operand_size: usize,
+    /// Index of the general-purpose register that is the MMIO read destination, or the MMIO
+    /// write source. Indexed the same way as `GuestRegisters` (0=RAX .. 15=R15). Meaningless
+    /// when `immediate` is `Some`.
+    pub gpr: usize,
+    /// The write value, for the immediate-operand forms (`MOV r/m, imm`). `None` for the
+    /// register-operand forms, where the value instead comes from `gpr`.
+    pub immediate: Option<u64>,
+    /// Total length of the decoded instruction, in bytes.
+    pub length: usize,
+}
+
+/// Errors returned while decoding an instruction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The opcode is not one of the MOV forms this decoder understands.
+    UnsupportedOpcode(u8),
+    /// The instruction bytes ended before decoding finished.
+    Truncated,
+}
+
+const REX_W: u8 = 0x08;
+const REX_R: u8 = 0x04;
+const OPERAND_SIZE_PREFIX: u8 = 0x66;
+
+const OPCODE_MOV_RM8_R8: u8 = 0x88;
+const OPCODE_MOV_RM_R: u8 = 0x89;
+const OPCODE_MOV_R8_RM8: u8 = 0x8a;
+const OPCODE_MOV_R_RM: u8 = 0x8b;
+const OPCODE_MOV_RM8_IMM8: u8 = 0xc6;
+const OPCODE_MOV_RM_IMM: u8 = 0xc7;
+
+/// Decode a single MOV instruction from `bytes`.
+///
+/// `bytes` must start at the first byte of the instruction and contain at least
+/// `exit_instruction_length` bytes, as reported by the #VE info for an EPT-violation exit.
+pub fn decode_mov(bytes: &[u8]) -> Result<DecodedMov, DecodeError> {
+    let mut idx = 0;
+    let mut rex = 0u8;
+    let mut operand_size = 4usize;
+
+    loop {
+        let b = *bytes.get(idx).ok_or(DecodeError::Truncated)?;
+        match b {
+            0x40..=0x4f => {
+                rex = b;
+                if rex & REX_W != 0 {
+                    operand_size = 8;
+                }
+                idx += 1;
+            }
+            OPERAND_SIZE_PREFIX => {
+                operand_size = 2;
+                idx += 1;
+            }
+            _ => break,
+        }
+    }
+
+    let opcode = *bytes.get(idx).ok_or(DecodeError::Truncated)?;
+    idx += 1;
+
+    let (direction, has_immediate) = match opcode {
+        OPCODE_MOV_RM8_R8 | OPCODE_MOV_RM_R => (MmioDirection::Write, false),
+        OPCODE_MOV_R8_RM8 | OPCODE_MOV_R_RM => (MmioDirection::Read, false),
+        OPCODE_MOV_RM8_IMM8 | OPCODE_MOV_RM_IMM => (MmioDirection::Write, true),
+        other => return Err(DecodeError::UnsupportedOpcode(other)),
+    };
+    if opcode == OPCODE_MOV_RM8_R8 || opcode == OPCODE_MOV_R8_RM8 || opcode == OPCODE_MOV_RM8_IMM8
+    {
+        operand_size = 1;
+    }
+
+    let modrm = *bytes.get(idx).ok_or(DecodeError::Truncated)?;
+    idx += 1;
+
+    let reg_field = (modrm >> 3) & 0x7;
+    let rm_field = modrm & 0x7;
+    let modbits = modrm >> 6;
+
+    // The immediate-operand forms only ever encode MOV via the /0 opcode extension; anything
+    // else in the reg field is a different instruction (e.g. the group-11 opcodes overlap with
+    // other encodings this decoder doesn't support).
+    if has_immediate && reg_field != 0 {
+        return Err(DecodeError::UnsupportedOpcode(opcode));
+    }
+
+    // Read the SIB byte when the addressing mode uses one (rm == 0b100, not register-direct).
+    // A SIB with mod == 0b00 and base == 0b101 has no base register and instead implies an
+    // extra disp32, just like ModRM rm == 0b101 does in the no-SIB case below.
+    let mut sib_disp32 = false;
+    if modbits != 0b11 && rm_field == 0b100 {
+        let sib = *bytes.get(idx).ok_or(DecodeError::Truncated)?;
+        idx += 1;
+        sib_disp32 = modbits == 0b00 && (sib & 0x7) == 0b101;
+    }
+
+    // Skip the displacement bytes implied by the ModRM mod field and addressing mode.
+    idx += match (modbits, rm_field) {
+        (0b00, 0b101) => 4, // RIP-relative / disp32-only
+        (0b00, _) if sib_disp32 => 4,
+        (0b00, _) => 0,
+        (0b01, _) => 1,
+        (0b10, _) => 4,
+        _ => 0,
+    };
+
+    if idx > bytes.len() {
+        return Err(DecodeError::Truncated);
+    }
+
+    // Without a REX prefix, reg 4..7 of the 8-bit register forms name AH/CH/DH/BH, which have no
+    // index of their own in `GuestRegisters`.
+    if operand_size == 1 && !has_immediate && rex == 0 && reg_field >= 4 {
+        return Err(DecodeError::UnsupportedOpcode(opcode));
+    }
+
+    let gpr = reg_field as usize | if rex & REX_R != 0 { 0x8 } else { 0 };
+
+    let immediate = if has_immediate {
+        // The 0xc7 form's immediate is imm16 under the 0x66 prefix, otherwise always imm32 (even
+        // for the 64-bit, REX.W operand-size form, where it is then sign-extended), matching
+        // 0xc6's fixed imm8.
+        let imm_size = match operand_size {
+            1 => 1,
+            2 => 2,
+            _ => 4,
+        };
+        let start = idx;
+        let end = idx + imm_size;
+        let imm_bytes = bytes.get(start..end).ok_or(DecodeError::Truncated)?;
+        idx = end;
+
+        let value = match imm_size {
+            1 => imm_bytes[0] as i8 as i64,
+            2 => i16::from_le_bytes([imm_bytes[0], imm_bytes[1]]) as i64,
+            _ => i32::from_le_bytes([imm_bytes[0], imm_bytes[1], imm_bytes[2], imm_bytes[3]]) as i64,
+        };
+        Some(if operand_size == 8 {
+            value as u64
+        } else {
+            value as u32 as u64
+        })
+    } else {
+        None
+    };
+
+    Ok(DecodedMov {
+        direction,
+        operand_size,
+        gpr,
+        immediate,
+        length: idx,
+    })
+}
@@ -0,0 +1,263 @@
+// Copyright (c) 2020-2022 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//! #VE (virtualization exception) dispatch.
+//!
+//! A #VE is raised on operations that a TD cannot perform directly and that the TDX module
+//! itself does not emulate (e.g. IO, CPUID, RDMSR/WRMSR, HLT). `tdcall_get_ve_info` reports why
+//! the exception was raised; `handle_virtualization_exception` decodes that information and
+//! drives the matching TDG.VP.VMCALL primitive on the caller's behalf.
+
+use super::tdcall::*;
+use super::tdx_mov_decode::{decode_mov, DecodeError, MmioDirection};
+
+/// General-purpose guest register state as saved on #VE entry by the caller's exception stub.
+///
+/// Indexed the same way as the x86-64 ModRM `reg`/`rm` field (0=RAX .. 7=RDI, 8=R8 .. 15=R15),
+/// so instruction decoders can resolve operands directly against this struct.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GuestRegisters {
+    pub rax: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rbx: u64,
+    pub rsp: u64,
+    pub rbp: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+}
+
+/// VMX basic exit reasons handled by the #VE dispatcher.
+///
+/// Details can be found in the Intel SDM Vol. 3C, Appendix C "VMX Basic Exit Reasons".
+const EXIT_REASON_CPUID: u32 = 10;
+const EXIT_REASON_HLT: u32 = 12;
+const EXIT_REASON_IO_INSTRUCTION: u32 = 30;
+const EXIT_REASON_RDMSR: u32 = 31;
+const EXIT_REASON_WRMSR: u32 = 32;
+const EXIT_REASON_EPT_VIOLATION: u32 = 48;
+
+/// Maximum length of an x86-64 instruction, used to size the fetch buffer for EPT-violation
+/// MMIO decoding.
+const MAX_INSTRUCTION_LENGTH: usize = 15;
+
+/// Bit layout of the IO-instruction `exit_qualification`, from the Intel SDM Vol. 3C,
+/// Table 27-5 "Exit Qualification for I/O Instructions".
+const IO_QUAL_SIZE_MASK: u64 = 0x7;
+const IO_QUAL_DIRECTION_IN: u64 = 1 << 3;
+const IO_QUAL_STRING: u64 = 1 << 4;
+const IO_QUAL_PORT_SHIFT: u32 = 16;
+
+/// Errors that can occur while dispatching a #VE.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TdVeError {
+    /// TDG.VP.VEINFO.GET returned an error.
+    TdCall(TdCallError),
+    /// The TDVMCALL used to emulate the faulting instruction returned an error.
+    TdVmcall(TdVmcallError),
+    /// The VMX exit reason is not one the dispatcher knows how to emulate.
+    UnsupportedExitReason(u32),
+    /// The IO instruction was a string or REP-prefixed form, which this dispatcher does not
+    /// decode.
+    UnsupportedIoString,
+    /// The IO instruction's access size field did not decode to 1, 2 or 4 bytes, or a decoded
+    /// MMIO operand size was not 1, 2, 4 or 8 bytes.
+    UnsupportedIoSize(u64),
+    /// The faulting instruction could not be decoded as a MOV accessing MMIO.
+    UnsupportedMmio(DecodeError),
+    /// The decoder consumed a different number of bytes than `exit_instruction_length`
+    /// reported, meaning the decode cannot be trusted.
+    DecodedLengthMismatch { decoded: usize, reported: u32 },
+    /// `exit_instruction_length` exceeds the longest possible x86-64 instruction, so the
+    /// faulting bytes cannot be fetched into the fixed-size decode buffer.
+    InstructionTooLong(u32),
+}
+
+impl From<TdCallError> for TdVeError {
+    fn from(e: TdCallError) -> Self {
+        TdVeError::TdCall(e)
+    }
+}
+
+impl From<TdVmcallError> for TdVeError {
+    fn from(e: TdVmcallError) -> Self {
+        TdVeError::TdVmcall(e)
+    }
+}
+
+/// Handle a #VE: read the exception info, emulate the instruction that caused it via the
+/// matching TDG.VP.VMCALL, and advance the guest RIP past it.
+///
+/// Details can be found in TDX Module ABI spec section 'TDG.VP.VEINFO.GET Leaf' and the TDX GHCI
+/// spec sections for the individual TDG.VP.VMCALL sub-functions dispatched below.
+pub fn handle_virtualization_exception(regs: &mut GuestRegisters) -> Result<(), TdVeError> {
+    let ve_info = tdcall_get_ve_info()?;
+
+    match ve_info.exit_reason {
+        EXIT_REASON_IO_INSTRUCTION => handle_io(&ve_info, regs)?,
+        EXIT_REASON_CPUID => {
+            // A 32-bit write, like any other, zero-extends into the full 64-bit register.
+            let info = tdvmcall_cpuid(regs.rax as u32, regs.rcx as u32);
+            regs.rax = info.eax as u64;
+            regs.rbx = info.ebx as u64;
+            regs.rcx = info.ecx as u64;
+            regs.rdx = info.edx as u64;
+        }
+        EXIT_REASON_RDMSR => {
+            let value = tdvmcall_rdmsr(regs.rcx as u32)?;
+            regs.rax = value & 0xffff_ffff;
+            regs.rdx = value >> 32;
+        }
+        EXIT_REASON_WRMSR => {
+            let value = (regs.rdx << 32) | (regs.rax & 0xffff_ffff);
+            tdvmcall_wrmsr(regs.rcx as u32, value)?;
+        }
+        EXIT_REASON_HLT => tdvmcall_halt(),
+        EXIT_REASON_EPT_VIOLATION => handle_mmio(&ve_info, regs)?,
+        reason => return Err(TdVeError::UnsupportedExitReason(reason)),
+    }
+
+    regs.rip += ve_info.exit_instruction_length as u64;
+    Ok(())
+}
+
+fn handle_io(ve_info: &TdVeInfo, regs: &mut GuestRegisters) -> Result<(), TdVeError> {
+    let qual = ve_info.exit_qualification;
+
+    if qual & IO_QUAL_STRING != 0 {
+        return Err(TdVeError::UnsupportedIoString);
+    }
+
+    let port = (qual >> IO_QUAL_PORT_SHIFT) as u16;
+    let is_in = qual & IO_QUAL_DIRECTION_IN != 0;
+
+    match (qual & IO_QUAL_SIZE_MASK, is_in) {
+        (0, true) => regs.rax = (regs.rax & !0xff) | tdvmcall_io_read_8(port) as u64,
+        (1, true) => regs.rax = (regs.rax & !0xffff) | tdvmcall_io_read_16(port) as u64,
+        // A 32-bit read, like any other, zero-extends into the full 64-bit register.
+        (3, true) => regs.rax = tdvmcall_io_read_32(port) as u64,
+        (0, false) => tdvmcall_io_write_8(port, regs.rax as u8),
+        (1, false) => tdvmcall_io_write_16(port, regs.rax as u16),
+        (3, false) => tdvmcall_io_write_32(port, regs.rax as u32),
+        (size, _) => return Err(TdVeError::UnsupportedIoSize(size)),
+    }
+
+    Ok(())
+}
+
+/// Emulate the MMIO access that caused an EPT-violation #VE: decode the faulting MOV from its
+/// instruction bytes, perform the access via `tdvmcall_mmio_read`/`tdvmcall_mmio_write` against
+/// the faulting GPA, and write a read's result back into the decoded destination register.
+fn handle_mmio(ve_info: &TdVeInfo, regs: &mut GuestRegisters) -> Result<(), TdVeError> {
+    let len = ve_info.exit_instruction_length as usize;
+    if len > MAX_INSTRUCTION_LENGTH {
+        return Err(TdVeError::InstructionTooLong(ve_info.exit_instruction_length));
+    }
+
+    let mut insn = [0u8; MAX_INSTRUCTION_LENGTH];
+    unsafe {
+        core::ptr::copy_nonoverlapping(ve_info.guest_la as *const u8, insn.as_mut_ptr(), len);
+    }
+
+    let mov = decode_mov(&insn[..len]).map_err(TdVeError::UnsupportedMmio)?;
+    // The hardware-reported length is what actually gets retired and is what advances RIP; if
+    // the decoder didn't consume exactly that many bytes, it misparsed the instruction and must
+    // not be trusted to resolve the right register or advance RIP correctly.
+    if mov.length != len {
+        return Err(TdVeError::DecodedLengthMismatch {
+            decoded: mov.length,
+            reported: ve_info.exit_instruction_length,
+        });
+    }
+
+    let addr = ve_info.guest_pa as usize;
+
+    match mov.direction {
+        MmioDirection::Write => {
+            let value = mov.immediate.unwrap_or_else(|| gpr_read(regs, mov.gpr));
+            match mov.operand_size {
+                1 => tdvmcall_mmio_write(addr as *const u8, value as u8),
+                2 => tdvmcall_mmio_write(addr as *const u16, value as u16),
+                4 => tdvmcall_mmio_write(addr as *const u32, value as u32),
+                8 => tdvmcall_mmio_write(addr as *const u64, value),
+                size => return Err(TdVeError::UnsupportedIoSize(size as u64)),
+            }
+        }
+        MmioDirection::Read => {
+            let value: u64 = match mov.operand_size {
+                1 => tdvmcall_mmio_read::<u8>(addr) as u64,
+                2 => tdvmcall_mmio_read::<u16>(addr) as u64,
+                4 => tdvmcall_mmio_read::<u32>(addr) as u64,
+                8 => tdvmcall_mmio_read::<u64>(addr),
+                size => return Err(TdVeError::UnsupportedIoSize(size as u64)),
+            };
+            gpr_write(regs, mov.gpr, mov.operand_size, value);
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a general-purpose register by ModRM index (0=RAX .. 15=R15).
+fn gpr_read(regs: &GuestRegisters, index: usize) -> u64 {
+    match index {
+        0 => regs.rax,
+        1 => regs.rcx,
+        2 => regs.rdx,
+        3 => regs.rbx,
+        4 => regs.rsp,
+        5 => regs.rbp,
+        6 => regs.rsi,
+        7 => regs.rdi,
+        8 => regs.r8,
+        9 => regs.r9,
+        10 => regs.r10,
+        11 => regs.r11,
+        12 => regs.r12,
+        13 => regs.r13,
+        14 => regs.r14,
+        _ => regs.r15,
+    }
+}
+
+/// Write `value` into a general-purpose register by ModRM index, truncated to `size` bytes.
+///
+/// Matches real x86-64 write semantics: 1- and 2-byte writes leave the rest of the register
+/// untouched, but a 4-byte write zero-extends into the full 64-bit register.
+fn gpr_write(regs: &mut GuestRegisters, index: usize, size: usize, value: u64) {
+    let reg = match index {
+        0 => &mut regs.rax,
+        1 => &mut regs.rcx,
+        2 => &mut regs.rdx,
+        3 => &mut regs.rbx,
+        4 => &mut regs.rsp,
+        5 => &mut regs.rbp,
+        6 => &mut regs.rsi,
+        7 => &mut regs.rdi,
+        8 => &mut regs.r8,
+        9 => &mut regs.r9,
+        10 => &mut regs.r10,
+        11 => &mut regs.r11,
+        12 => &mut regs.r12,
+        13 => &mut regs.r13,
+        14 => &mut regs.r14,
+        _ => &mut regs.r15,
+    };
+    *reg = match size {
+        1 => (*reg & !0xff) | (value & 0xff),
+        2 => (*reg & !0xffff) | (value & 0xffff),
+        4 => value & 0xffff_ffff,
+        _ => value,
+    };
+}